@@ -1,7 +1,57 @@
 use load_config::load_config;
 use rocket::figment::Figment;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Where a listener binds: a TCP `IpAddr` (paired with the sibling `port`
+/// field), or a Unix domain socket given as `unix:/path/to/socket`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(IpAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => Ok(ListenAddress::Tcp(s.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(ip) => write!(f, "{ip}"),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for ListenAddress {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
@@ -9,18 +59,146 @@ pub struct Config {
     pub client: ClientConfig,
     pub agent: AgentConfig,
     pub tls: TlsConfig,
+    #[serde(default)]
+    pub hawk: HawkConfig,
+}
+
+/// Shared keys and clock-skew policy for the HAWK bearer-auth fallback, for
+/// clients (internal tooling, sidecars) that cannot present an RA-TLS cert.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HawkConfig {
+    #[serde(default)]
+    pub keys: Vec<HawkKey>,
+    #[serde(default = "default_max_clock_skew_secs")]
+    pub max_clock_skew_secs: i64,
+}
+
+fn default_max_clock_skew_secs() -> i64 {
+    60
+}
+
+/// One HAWK credential: the shared key for `id`, and the `app_id` it
+/// resolves to once the MAC checks out.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HawkKey {
+    pub id: String,
+    pub app_id: String,
+    pub key: String,
+    #[serde(default = "default_hawk_algorithm")]
+    pub algorithm: String,
+}
+
+fn default_hawk_algorithm() -> String {
+    "sha256".to_string()
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AuthConfig {
-    pub address: IpAddr,
+    /// `unix:/path/to/socket` binds a Unix domain socket; anything else is
+    /// parsed as an `IpAddr` and paired with `port`.
+    pub address: ListenAddress,
     pub port: u16,
+    /// Allowlist of caller -> target scopes; defaults to empty (deny all).
+    #[serde(default)]
+    pub policies: Vec<PolicyRule>,
+    /// When true, terminate mTLS in-process (see `mtls` module) instead of
+    /// trusting the `x-client-cert`/`x-client-verify` headers nginx injects.
+    /// The header path still runs as a fallback when no client cert was
+    /// presented on the connection.
+    #[serde(default)]
+    pub in_process_mtls: bool,
+    /// Whether `auth_handler` accepts `x-client-cert`/`x-client-verify`
+    /// headers at all. Defaults to true for deployments fronted by nginx;
+    /// set to false once every caller can present a cert (or a Hawk
+    /// credential) directly, so a client that reaches the auth service
+    /// without going through nginx can't forge these headers to bypass
+    /// cert verification entirely.
+    #[serde(default = "default_allow_header_fallback")]
+    pub allow_header_fallback: bool,
+}
+
+fn default_allow_header_fallback() -> bool {
+    true
+}
+
+/// A single authorization scope: which target(s) a caller `app_id` may reach.
+///
+/// `app_id`, `instance_id` and `port` accept the literal `*` as a wildcard.
+/// Leaving `port` unset also matches any port.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PolicyRule {
+    pub caller_app_id: String,
+    #[serde(default = "wildcard")]
+    pub app_id: String,
+    #[serde(default = "wildcard")]
+    pub instance_id: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+fn wildcard() -> String {
+    "*".to_string()
+}
+
+impl PolicyRule {
+    /// Returns true if this rule permits `caller_app_id` to reach `target`.
+    pub fn matches(&self, caller_app_id: &str, target: &TargetInfo) -> bool {
+        fn field_matches(pattern: &str, value: &str) -> bool {
+            pattern == "*" || pattern == value
+        }
+
+        field_matches(&self.caller_app_id, caller_app_id)
+            && field_matches(&self.app_id, &target.app_id)
+            && field_matches(&self.instance_id, &target.instance_id)
+            && self.port.map_or(true, |port| port == target.port)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ClientConfig {
-    pub address: IpAddr,
+    /// `unix:/path/to/socket` binds a Unix domain socket; anything else is
+    /// parsed as an `IpAddr` and paired with `port`.
+    pub address: ListenAddress,
+    pub port: u16,
+    /// Per-hostname RA-TLS identities for fronting multiple apps behind one
+    /// mesh-proxy instance; see the `sni` module. Empty by default, in which
+    /// case every connection is served the top-level `TlsConfig` cert.
+    #[serde(default)]
+    pub sni_routes: Vec<SniRoute>,
+}
+
+/// Maps one SNI hostname (typically `<app_id>.<gateway_domain>`) to the
+/// RA-TLS certificate/key the proxy should present for it, and to the
+/// `app_id`/`instance_id`/`port` the hostname actually routes to. This is
+/// the single place (config, not a client-supplied header) that binds a
+/// hostname to a target, so `auth_handler` can resolve the real destination
+/// of a proxied request instead of trusting a caller-supplied header.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SniRoute {
+    pub hostname: String,
+    pub app_id: String,
+    pub instance_id: String,
+    #[serde(default)]
     pub port: u16,
+    pub cert_file: String,
+    pub key_file: String,
+}
+
+/// Looks up the `TargetInfo` a verified SNI hostname routes to, from the
+/// operator-defined `sni_routes` table rather than from any part of the
+/// request being authorized. Callers must pass the hostname the TLS
+/// handshake actually negotiated (see `server::VerifiedSni`), not an HTTP
+/// `Host` header, which a caller can set independently of the SNI it
+/// connected with.
+pub fn resolve_target(routes: &[SniRoute], hostname: &str) -> Option<TargetInfo> {
+    routes
+        .iter()
+        .find(|route| route.hostname == hostname)
+        .map(|route| TargetInfo {
+            app_id: route.app_id.clone(),
+            instance_id: route.instance_id.clone(),
+            port: route.port,
+        })
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -33,6 +211,15 @@ pub struct TlsConfig {
     pub cert_file: String,
     pub key_file: String,
     pub ca_file: String,
+    /// How often the `cert_store` module polls `cert_file`/`key_file`/
+    /// `ca_file` for changes and hot-reloads them. Set to 0 to disable
+    /// watching and load the material once at startup.
+    #[serde(default = "default_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+}
+
+fn default_reload_interval_secs() -> u64 {
+    30
 }
 
 /// Target information extracted from headers
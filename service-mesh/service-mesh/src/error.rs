@@ -0,0 +1,83 @@
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::Responder;
+use rocket::serde::json::Json;
+use rocket::Request;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Per-request correlation id, generated once and echoed back on both
+/// success and failure so a rejected request can be matched to server logs.
+/// Modeled on the `X-KANIDM-OPID` convention.
+pub struct OpId(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OpId {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let op_id = request.local_cache(|| Uuid::new_v4().to_string());
+        Outcome::Success(OpId(op_id.clone()))
+    }
+}
+
+/// Stable, machine-readable reason a request was rejected by `auth_handler`.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthErrorReason {
+    MissingVerify,
+    VerifyNotSuccess,
+    MissingCert,
+    CertParseFailed,
+    NoAppId,
+    PolicyDenied,
+}
+
+impl AuthErrorReason {
+    fn status(self) -> Status {
+        match self {
+            AuthErrorReason::PolicyDenied => Status::Forbidden,
+            _ => Status::Unauthorized,
+        }
+    }
+}
+
+/// A structured, JSON-bodied auth failure carrying the op-id so callers and
+/// log aggregators can diagnose rejections without guessing.
+pub struct AuthError {
+    reason: AuthErrorReason,
+    op_id: String,
+}
+
+impl AuthError {
+    pub fn new(reason: AuthErrorReason, op_id: impl Into<String>) -> Self {
+        Self {
+            reason,
+            op_id: op_id.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuthErrorBody {
+    reason: AuthErrorReason,
+    op_id: String,
+}
+
+impl<'r> Responder<'r, 'static> for AuthError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let body = AuthErrorBody {
+            reason: self.reason,
+            op_id: self.op_id.clone(),
+        };
+        let mut response = Json(body).respond_to(request)?;
+        response.set_status(self.reason.status());
+        response.set_header(Header::new("x-dstack-op-id", self.op_id));
+        Ok(response)
+    }
+}
+
+/// Builds the shared `x-dstack-op-id` header attached to every response.
+pub fn op_id_header(op_id: &str) -> Header<'static> {
+    Header::new("x-dstack-op-id", op_id.to_string())
+}
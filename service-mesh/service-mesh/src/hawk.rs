@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::config::HawkConfig;
+
+/// Resolved identity of a request that passed HAWK MAC verification.
+pub struct VerifiedHawk {
+    pub app_id: String,
+}
+
+/// A parsed `Authorization: Hawk id="...", ts="...", nonce="...", mac="..."`
+/// header.
+struct HawkHeader {
+    id: String,
+    ts: i64,
+    nonce: String,
+    mac: String,
+}
+
+/// Short-lived replay cache of `(id, nonce)` pairs seen within the clock-skew
+/// window. Entries older than twice the configured skew are pruned on each
+/// verification, so the set stays bounded without a background task.
+static SEEN_NONCES: Lazy<Mutex<HashMap<(String, String), i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Verifies a HAWK bearer request and resolves it to the `app_id` the
+/// matching key belongs to, the same way the cert path does, so downstream
+/// policy checks are uniform across auth methods.
+pub fn verify(
+    config: &HawkConfig,
+    authorization: &str,
+    method: &str,
+    path_and_query: &str,
+    host: &str,
+    port: u16,
+    payload_hash: Option<&str>,
+) -> Result<VerifiedHawk> {
+    let header = parse_header(authorization)?;
+
+    let key_entry = config
+        .keys
+        .iter()
+        .find(|k| k.id == header.id)
+        .context("unknown hawk id")?;
+    if key_entry.algorithm != "sha256" {
+        bail!("unsupported hawk algorithm: {}", key_entry.algorithm);
+    }
+
+    let now: i64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system clock before unix epoch")?
+        .as_secs()
+        .try_into()
+        .context("system clock overflowed i64 seconds")?;
+    if (now - header.ts).abs() > config.max_clock_skew_secs {
+        bail!("ts outside allowed clock-skew window");
+    }
+
+    check_nonce_not_replayed(&header.id, &header.nonce, header.ts, config.max_clock_skew_secs)?;
+
+    let normalized = normalized_string(
+        header.ts,
+        &header.nonce,
+        method,
+        path_and_query,
+        host,
+        port,
+        payload_hash,
+    );
+    let expected_mac = compute_mac(&key_entry.key, &normalized)?;
+    let provided_mac = base64::decode(&header.mac).context("mac is not valid base64")?;
+
+    if expected_mac.len() != provided_mac.len()
+        || expected_mac.ct_eq(&provided_mac).unwrap_u8() != 1
+    {
+        bail!("mac mismatch");
+    }
+
+    // Only record the nonce once the MAC has actually checked out. Recording
+    // it earlier would let anyone who merely observes or guesses a
+    // legitimate `(id, nonce)` pair pre-register it with a garbage mac,
+    // permanently poisoning that nonce against the real request.
+    record_nonce(&header.id, &header.nonce, header.ts, config.max_clock_skew_secs);
+
+    Ok(VerifiedHawk {
+        app_id: key_entry.app_id.clone(),
+    })
+}
+
+fn parse_header(authorization: &str) -> Result<HawkHeader> {
+    let rest = authorization
+        .strip_prefix("Hawk ")
+        .context("not a Hawk authorization scheme")?;
+
+    let mut id = None;
+    let mut ts = None;
+    let mut nonce = None;
+    let mut mac = None;
+    for part in rest.split(',') {
+        let Some((key, value)) = part.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim() {
+            "id" => id = Some(value.to_string()),
+            "ts" => ts = Some(value.parse::<i64>().context("invalid ts")?),
+            "nonce" => nonce = Some(value.to_string()),
+            "mac" => mac = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    Ok(HawkHeader {
+        id: id.context("missing id in Hawk header")?,
+        ts: ts.context("missing ts in Hawk header")?,
+        nonce: nonce.context("missing nonce in Hawk header")?,
+        mac: mac.context("missing mac in Hawk header")?,
+    })
+}
+
+/// Newline-joined normalized request string the MAC is computed over:
+/// timestamp, nonce, uppercased method, request path+query, host, port, and
+/// an optional base64 payload hash.
+fn normalized_string(
+    ts: i64,
+    nonce: &str,
+    method: &str,
+    path_and_query: &str,
+    host: &str,
+    port: u16,
+    payload_hash: Option<&str>,
+) -> String {
+    format!(
+        "{ts}\n{nonce}\n{}\n{path_and_query}\n{host}\n{port}\n{}\n",
+        method.to_uppercase(),
+        payload_hash.unwrap_or("")
+    )
+}
+
+fn compute_mac(key: &str, message: &str) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).context("invalid hawk key")?;
+    mac.update(message.as_bytes());
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Rejects a `(id, nonce)` pair already seen within the replay window,
+/// without recording it — recording happens separately, only once the mac
+/// is known to be valid (see `record_nonce`).
+fn check_nonce_not_replayed(id: &str, nonce: &str, ts: i64, max_clock_skew_secs: i64) -> Result<()> {
+    let mut seen = SEEN_NONCES.lock().unwrap();
+    seen.retain(|_, seen_ts| (ts - *seen_ts).abs() <= max_clock_skew_secs * 2);
+    if seen.contains_key(&(id.to_string(), nonce.to_string())) {
+        bail!("nonce replay detected");
+    }
+    Ok(())
+}
+
+fn record_nonce(id: &str, nonce: &str, ts: i64, max_clock_skew_secs: i64) {
+    let mut seen = SEEN_NONCES.lock().unwrap();
+    seen.retain(|_, seen_ts| (ts - *seen_ts).abs() <= max_clock_skew_secs * 2);
+    seen.insert((id.to_string(), nonce.to_string()), ts);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HawkKey;
+
+    fn config() -> HawkConfig {
+        HawkConfig {
+            keys: vec![HawkKey {
+                id: "sidecar-1".to_string(),
+                app_id: "app-abc".to_string(),
+                key: "super-secret".to_string(),
+                algorithm: "sha256".to_string(),
+            }],
+            max_clock_skew_secs: 60,
+        }
+    }
+
+    fn sign(ts: i64, nonce: &str, method: &str, path: &str, host: &str, port: u16) -> String {
+        let normalized = normalized_string(ts, nonce, method, path, host, port, None);
+        let mac = compute_mac("super-secret", &normalized).unwrap();
+        base64::encode(mac)
+    }
+
+    #[test]
+    fn valid_mac_resolves_app_id() {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let mac = sign(ts, "nonce-valid", "GET", "/auth", "mesh.internal", 8443);
+        let header = format!(
+            "Hawk id=\"sidecar-1\", ts=\"{ts}\", nonce=\"nonce-valid\", mac=\"{mac}\""
+        );
+
+        let verified = verify(&config(), &header, "GET", "/auth", "mesh.internal", 8443, None).unwrap();
+        assert_eq!(verified.app_id, "app-abc");
+    }
+
+    #[test]
+    fn replayed_nonce_is_rejected() {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let mac = sign(ts, "nonce-replay", "GET", "/auth", "mesh.internal", 8443);
+        let header = format!(
+            "Hawk id=\"sidecar-1\", ts=\"{ts}\", nonce=\"nonce-replay\", mac=\"{mac}\""
+        );
+
+        assert!(verify(&config(), &header, "GET", "/auth", "mesh.internal", 8443, None).is_ok());
+        assert!(verify(&config(), &header, "GET", "/auth", "mesh.internal", 8443, None).is_err());
+    }
+
+    #[test]
+    fn forged_mac_does_not_poison_nonce_for_legitimate_retry() {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let valid_mac = sign(ts, "nonce-poison", "GET", "/auth", "mesh.internal", 8443);
+        let forged_header = format!(
+            "Hawk id=\"sidecar-1\", ts=\"{ts}\", nonce=\"nonce-poison\", mac=\"AAAAAAAAAAAAAAAAAAAAAA==\""
+        );
+        assert!(verify(&config(), &forged_header, "GET", "/auth", "mesh.internal", 8443, None).is_err());
+
+        let legit_header = format!(
+            "Hawk id=\"sidecar-1\", ts=\"{ts}\", nonce=\"nonce-poison\", mac=\"{valid_mac}\""
+        );
+        let verified = verify(&config(), &legit_header, "GET", "/auth", "mesh.internal", 8443, None).unwrap();
+        assert_eq!(verified.app_id, "app-abc");
+    }
+
+    #[test]
+    fn stale_timestamp_is_rejected() {
+        let ts = 0;
+        let mac = sign(ts, "nonce-stale", "GET", "/auth", "mesh.internal", 8443);
+        let header = format!(
+            "Hawk id=\"sidecar-1\", ts=\"{ts}\", nonce=\"nonce-stale\", mac=\"{mac}\""
+        );
+
+        assert!(verify(&config(), &header, "GET", "/auth", "mesh.internal", 8443, None).is_err());
+    }
+}
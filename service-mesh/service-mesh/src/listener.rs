@@ -0,0 +1,29 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Removes a stale socket file left behind by a previous, uncleanly-stopped
+/// run so a fresh `bind` can reuse the path.
+pub fn remove_stale_socket(path: &Path) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Deletes the Unix domain socket file on drop, so a clean shutdown doesn't
+/// leave it behind for the next start to trip over.
+pub struct UnixSocketCleanup(PathBuf);
+
+impl UnixSocketCleanup {
+    pub fn new(path: PathBuf) -> Self {
+        Self(path)
+    }
+}
+
+impl Drop for UnixSocketCleanup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
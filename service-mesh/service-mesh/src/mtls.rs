@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use ra_tls::traits::CertExt;
+use rocket::http::Status;
+use rocket::mtls::Certificate;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{async_trait, Request};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, SignatureScheme};
+use tracing::warn;
+
+use crate::cert_store::CertStore;
+use crate::config::{SniRoute, TlsConfig};
+use crate::sni::SniCertResolver;
+
+/// Request guard exposing the `app_id` of a client certificate that Rocket
+/// has already chain-verified in-process, bypassing the nginx header-trust
+/// path entirely. Only resolves to `Success` when the listener was launched
+/// with mutual TLS enabled and the peer actually presented a certificate;
+/// otherwise it forwards, letting the nginx `auth_request` path run instead.
+pub struct VerifiedAppId(pub String);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for VerifiedAppId {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let cert = match request.guard::<Certificate<'_>>().await {
+            Outcome::Success(cert) => cert,
+            Outcome::Forward(status) => return Outcome::Forward(status),
+            Outcome::Error(_) => return Outcome::Forward(Status::Unauthorized),
+        };
+        match app_id_from_der(cert.as_bytes()) {
+            Ok(app_id) => Outcome::Success(VerifiedAppId(app_id)),
+            Err(e) => {
+                warn!("verified client cert has no usable app_id: {e}");
+                Outcome::Error((Status::Unauthorized, e))
+            }
+        }
+    }
+}
+
+fn app_id_from_der(der: &[u8]) -> Result<String> {
+    let (_, cert) =
+        x509_parser::parse_x509_certificate(der).context("failed to parse peer leaf certificate")?;
+    let app_id_bytes = cert
+        .get_app_id()
+        .context("failed to read app_id extension from peer certificate")?
+        .context("peer certificate has no app_id")?;
+    Ok(hex::encode(app_id_bytes))
+}
+
+/// Merges the TLS material from `TlsConfig` into a Rocket figment so the
+/// listener terminates mTLS itself instead of relying on nginx, rooting
+/// client certificate validation at `ca_file`. `mandatory` controls
+/// whether Rocket rejects the handshake outright when no client cert is
+/// presented, versus letting the request through for a header-based
+/// fallback to decide.
+pub fn with_mutual_tls(
+    base: rocket::figment::Figment,
+    tls: &TlsConfig,
+    mandatory: bool,
+) -> rocket::figment::Figment {
+    base.merge(("tls.certs", tls.cert_file.clone()))
+        .merge(("tls.key", tls.key_file.clone()))
+        .merge(("tls.mutual.ca_certs", tls.ca_file.clone()))
+        .merge(("tls.mutual.mandatory", mandatory))
+}
+
+/// Client-cert verifier for the client-facing listener: delegates chain
+/// validation to rustls' standard `WebPkiClientVerifier`, then additionally
+/// requires the verified leaf to carry a parseable RA-TLS `app_id`. A cert
+/// issued by the right CA but missing the extension fails the handshake
+/// outright instead of being accepted and only rejected later by a handler.
+#[derive(Debug)]
+pub struct AppIdClientCertVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+}
+
+impl AppIdClientCertVerifier {
+    /// Builds a verifier rooted at the CA certificate(s) in `ca_file`.
+    /// `mandatory` mirrors `tls.mutual.mandatory`: when false, connections
+    /// without a client cert are still accepted (for the header-based
+    /// fallback to arbitrate), they just won't resolve an `app_id` here.
+    pub fn build(ca_file: &str, mandatory: bool) -> Result<Arc<Self>> {
+        let ca_pem = std::fs::read(ca_file).with_context(|| format!("failed to read {ca_file}"))?;
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut ca_pem.as_slice()) {
+            roots
+                .add(cert.context("failed to parse CA certificate")?)
+                .context("failed to add CA certificate to root store")?;
+        }
+        let mut builder = WebPkiClientVerifier::builder(Arc::new(roots));
+        if !mandatory {
+            builder = builder.allow_unauthenticated();
+        }
+        let inner = builder
+            .build()
+            .context("failed to build client certificate verifier")?;
+        Ok(Arc::new(Self { inner }))
+    }
+}
+
+impl ClientCertVerifier for AppIdClientCertVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.inner.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self.inner.verify_client_cert(end_entity, intermediates, now)?;
+        app_id_from_der(end_entity)
+            .map_err(|e| rustls::Error::General(format!("peer certificate rejected: {e}")))?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Returns the `app_id` of the already-verified peer certificate on an
+/// established connection, for the client listener, which terminates TLS
+/// itself rather than going through Rocket's `mtls::Certificate` guard.
+pub fn app_id_of_peer(conn: &rustls::ServerConnection) -> Result<String> {
+    let certs = conn
+        .peer_certificates()
+        .context("connection has no peer certificates")?;
+    let leaf = certs.first().context("peer certificate chain is empty")?;
+    app_id_from_der(leaf)
+}
+
+/// Builds the rustls `ServerConfig` the client listener terminates mTLS
+/// with: client certs verified against `ca_file` through
+/// `AppIdClientCertVerifier`, and the server identity resolved per-SNI by
+/// `SniCertResolver`. The default identity (no SNI match, or none
+/// presented) is sourced from `cert_store` rather than a copy of
+/// `TlsConfig` read once at startup, so a hot-reloaded rotation changes
+/// what this listener actually serves, not just what `/health` reports.
+pub fn build_client_tls_config(
+    cert_store: &Arc<CertStore>,
+    tls: &TlsConfig,
+    sni_routes: &[SniRoute],
+    mandatory: bool,
+) -> Result<rustls::ServerConfig> {
+    let verifier = AppIdClientCertVerifier::build(&tls.ca_file, mandatory)?;
+    let resolver = Arc::new(
+        SniCertResolver::new(cert_store.clone(), sni_routes)
+            .context("failed to build SNI cert resolver")?,
+    );
+    Ok(rustls::ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_cert_resolver(resolver))
+}
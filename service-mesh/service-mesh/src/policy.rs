@@ -0,0 +1,66 @@
+use crate::config::{PolicyRule, TargetInfo};
+
+/// Evaluates whether a caller `app_id` may reach a given mesh target.
+///
+/// Rules are matched in order; the first rule whose fields all match the
+/// caller and target wins. A field value of `*` matches anything, modeled
+/// on the scope-set style used by HAWK/OAuth `AuthSource` allowlists.
+pub struct Policy {
+    rules: Vec<PolicyRule>,
+}
+
+impl Policy {
+    pub fn new(rules: Vec<PolicyRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Returns true if `caller_app_id` is permitted to reach `target`.
+    pub fn is_allowed(&self, caller_app_id: &str, target: &TargetInfo) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.matches(caller_app_id, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(app_id: &str, instance_id: &str, port: u16) -> TargetInfo {
+        TargetInfo {
+            app_id: app_id.to_string(),
+            instance_id: instance_id.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn wildcard_app_id_allows_all() {
+        let policy = Policy::new(vec![PolicyRule {
+            caller_app_id: "caller".to_string(),
+            app_id: "*".to_string(),
+            instance_id: "*".to_string(),
+            port: None,
+        }]);
+        assert!(policy.is_allowed("caller", &target("anything", "inst", 8080)));
+        assert!(!policy.is_allowed("other", &target("anything", "inst", 8080)));
+    }
+
+    #[test]
+    fn exact_port_is_enforced() {
+        let policy = Policy::new(vec![PolicyRule {
+            caller_app_id: "caller".to_string(),
+            app_id: "target".to_string(),
+            instance_id: "*".to_string(),
+            port: Some(8080),
+        }]);
+        assert!(policy.is_allowed("caller", &target("target", "inst", 8080)));
+        assert!(!policy.is_allowed("caller", &target("target", "inst", 9090)));
+    }
+
+    #[test]
+    fn no_matching_rule_denies() {
+        let policy = Policy::new(vec![]);
+        assert!(!policy.is_allowed("caller", &target("target", "inst", 8080)));
+    }
+}
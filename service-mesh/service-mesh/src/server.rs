@@ -1,16 +1,27 @@
-use anyhow::{bail, Context, Result};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
 use ra_tls::traits::CertExt;
 use rocket::figment::providers::Serialized;
 use rocket::http::{Header, Status};
 use rocket::request::{FromRequest, Outcome};
 use rocket::response::Responder;
 use rocket::response::Response;
-use rocket::{get, routes, Request};
+use rocket::serde::json::Json;
+use rocket::{get, routes, Request, State};
 use tracing::{info, warn};
 
+use crate::cert_store::CertStore;
+use crate::config::{self, AuthConfig, ClientConfig, HawkConfig, ListenAddress, SniRoute};
+use crate::error::{op_id_header, AuthError, AuthErrorReason, OpId};
+use crate::listener::{remove_stale_socket, UnixSocketCleanup};
+use crate::mtls::{app_id_of_peer, build_client_tls_config, with_mutual_tls, VerifiedAppId};
+use crate::policy::Policy;
+
 /// Custom responder that returns status with headers
 pub struct AuthSuccessResponse {
     app_id: String,
+    op_id: String,
 }
 
 impl<'r> Responder<'r, 'static> for AuthSuccessResponse {
@@ -18,6 +29,7 @@ impl<'r> Responder<'r, 'static> for AuthSuccessResponse {
         Response::build()
             .status(Status::Ok)
             .header(Header::new("x-dstack-app-id", self.app_id))
+            .header(op_id_header(&self.op_id))
             .ok()
     }
 }
@@ -44,74 +56,452 @@ impl<'r> FromRequest<'r> for AuthHeaders {
     }
 }
 
+/// Custom request guard for the TLS SNI hostname the proxied connection
+/// actually negotiated. Deliberately does *not* read the HTTP `Host`
+/// header: nginx proxies `Host` verbatim from the client's own request, so
+/// a caller can set it to anything regardless of which cert/SNI it
+/// connected with — that's exactly as forgeable as the `x-target-app-id`
+/// header this guard replaced. `x-dstack-sni` is a distinct header nginx
+/// must set itself from its `$ssl_server_name` variable (the hostname the
+/// TLS handshake actually resolved, i.e. what `SniCertResolver` on the
+/// client listener served a cert for), and must overwrite rather than
+/// forward any client-supplied value of the same name — the same trust
+/// contract `AuthHeaders` already relies on for `x-client-cert`. The
+/// `app_id`/`instance_id`/`port` it maps to are looked up from the
+/// operator-defined `client.sni_routes` table (see
+/// `config::resolve_target`), never trusted from a caller-supplied value.
+pub struct VerifiedSni(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for VerifiedSni {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let sni = request
+            .headers()
+            .get_one("x-dstack-sni")
+            .unwrap_or_default()
+            .to_string();
+
+        Outcome::Success(VerifiedSni(sni))
+    }
+}
+
+/// Custom request guard capturing everything needed to verify a HAWK
+/// bearer request: the raw `Authorization` header plus the request
+/// components the MAC is computed over.
+pub struct HawkRequest {
+    pub authorization: Option<String>,
+    pub method: String,
+    pub path_and_query: String,
+    pub host: String,
+    pub port: u16,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for HawkRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let authorization = request
+            .headers()
+            .get_one("authorization")
+            .map(|s| s.to_string());
+
+        // A `Host` header without an explicit port is the normal case for
+        // default-port (80/443) requests, not a malformed one — fall back
+        // to the port this listener is actually bound on rather than 0, or
+        // HAWK verification would silently break for every such request.
+        let listener_port = request.rocket().config().port;
+        let host_header = request.headers().get_one("host").unwrap_or_default();
+        let (host, port) = match host_header.split_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse().unwrap_or(listener_port)),
+            None => (host_header.to_string(), listener_port),
+        };
+
+        Outcome::Success(HawkRequest {
+            authorization,
+            method: request.method().as_str().to_string(),
+            path_and_query: request.uri().to_string(),
+            host,
+            port,
+        })
+    }
+}
+
+/// Managed-state flag mirroring `AuthConfig::allow_header_fallback`, so
+/// `auth_handler` can reject `x-client-cert`/`x-client-verify` headers
+/// outright once an operator has disabled the fallback, rather than
+/// trusting them from whatever reached the service.
+struct AllowHeaderFallback(bool);
+
 /// Auth endpoint for nginx auth_request integration
 #[get("/auth")]
-async fn auth_handler(headers: AuthHeaders) -> Result<AuthSuccessResponse, Status> {
-    // Extract client certificate from headers (passed by nginx)
-    let cert_header = headers.client_cert.as_ref();
-    let verify_header = headers.client_verify.as_ref();
-
-    let Some(verify) = verify_header else {
-        warn!("Missing verify header");
-        return Err(Status::Unauthorized);
+async fn auth_handler(
+    mtls_app_id: Option<VerifiedAppId>,
+    headers: AuthHeaders,
+    hawk_request: HawkRequest,
+    sni: VerifiedSni,
+    policy: &State<Policy>,
+    hawk_config: &State<HawkConfig>,
+    sni_routes: &State<Vec<SniRoute>>,
+    allow_header_fallback: &State<AllowHeaderFallback>,
+    op_id: OpId,
+) -> Result<AuthSuccessResponse, AuthError> {
+    let OpId(op_id) = op_id;
+
+    let app_id = if let Some(VerifiedAppId(app_id)) = mtls_app_id {
+        info!(op_id, "Auth via in-process mTLS for app_id: {app_id}");
+        app_id
+    } else if headers.client_cert.is_some() || headers.client_verify.is_some() {
+        if !allow_header_fallback.0 {
+            warn!(op_id, "Rejecting x-client-cert/x-client-verify headers: header fallback is disabled by config");
+            return Err(AuthError::new(AuthErrorReason::MissingCert, op_id));
+        }
+        resolve_app_id_from_headers(&headers, &op_id)
+            .await
+            .map_err(|reason| AuthError::new(reason, op_id.clone()))?
+    } else if let Some(authorization) = hawk_request.authorization.as_ref() {
+        resolve_app_id_from_hawk(authorization, &hawk_request, hawk_config, &op_id)
+            .map_err(|reason| AuthError::new(reason, op_id.clone()))?
+    } else {
+        warn!(op_id, "No credentials presented (no client cert, no Hawk authorization)");
+        return Err(AuthError::new(AuthErrorReason::MissingCert, op_id));
     };
-    if verify != "SUCCESS" {
-        warn!("Verify header is not SUCCESS");
-        return Err(Status::Unauthorized);
+
+    let VerifiedSni(sni) = sni;
+    let Some(target_info) = config::resolve_target(sni_routes, &sni) else {
+        warn!(op_id, "No sni_routes entry for SNI {sni}, cannot resolve target");
+        return Err(AuthError::new(AuthErrorReason::PolicyDenied, op_id));
+    };
+    if !policy.is_allowed(&app_id, &target_info) {
+        warn!(op_id, "Policy denied app_id {app_id} -> {target_info:?}");
+        return Err(AuthError::new(AuthErrorReason::PolicyDenied, op_id));
     }
-    let Some(cert_pem) = cert_header else {
-        warn!("Missing cert header");
-        return Err(Status::Unauthorized);
+    info!(op_id, "Auth successful for app_id: {app_id}");
+    Ok(AuthSuccessResponse { app_id, op_id })
+}
+
+/// Falls back to nginx's `x-client-cert`/`x-client-verify` header convention
+/// when the connection was not terminated with in-process mTLS.
+async fn resolve_app_id_from_headers(
+    headers: &AuthHeaders,
+    op_id: &str,
+) -> Result<String, AuthErrorReason> {
+    let Some(verify) = headers.client_verify.as_ref() else {
+        warn!(op_id, "Missing verify header");
+        return Err(AuthErrorReason::MissingVerify);
     };
-    // Parse and verify certificate
-    match parse_and_verify_cert(cert_pem).await {
-        Ok(app_id) => {
-            info!("Auth successful for app_id: {app_id}");
-            Ok(AuthSuccessResponse { app_id })
-        }
-        Err(e) => {
-            warn!("Auth failed: {e}");
-            Err(Status::Unauthorized)
-        }
+    if verify != "SUCCESS" {
+        warn!(op_id, "Verify header is not SUCCESS");
+        return Err(AuthErrorReason::VerifyNotSuccess);
     }
+    let Some(cert_pem) = headers.client_cert.as_ref() else {
+        warn!(op_id, "Missing cert header");
+        return Err(AuthErrorReason::MissingCert);
+    };
+    parse_and_verify_cert(cert_pem, op_id).await
 }
 
-async fn parse_and_verify_cert(cert_pem: &str) -> Result<String> {
-    let decoded = urlencoding::decode(cert_pem).context("Failed to decode certificate")?;
-    let (_, ca_pem) =
-        x509_parser::pem::parse_x509_pem(decoded.as_bytes()).context("Failed to parse ca cert")?;
-    let cert = ca_pem.parse_x509().context("Failed to parse ca cert")?;
-    let Some(app_id_bytes) = cert
+/// Falls back to HAWK MAC bearer auth for clients that cannot present an
+/// RA-TLS cert, resolving the HAWK `id` to an `app_id` the same way the
+/// cert path does so downstream policy checks are uniform.
+fn resolve_app_id_from_hawk(
+    authorization: &str,
+    hawk_request: &HawkRequest,
+    hawk_config: &HawkConfig,
+    op_id: &str,
+) -> Result<String, AuthErrorReason> {
+    crate::hawk::verify(
+        hawk_config,
+        authorization,
+        &hawk_request.method,
+        &hawk_request.path_and_query,
+        &hawk_request.host,
+        hawk_request.port,
+        None,
+    )
+    .map(|verified| verified.app_id)
+    .map_err(|e| {
+        warn!(op_id, "Hawk auth failed: {e}");
+        AuthErrorReason::MissingCert
+    })
+}
+
+async fn parse_and_verify_cert(cert_pem: &str, op_id: &str) -> Result<String, AuthErrorReason> {
+    let decoded = urlencoding::decode(cert_pem).map_err(|e| {
+        warn!(op_id, "Failed to decode certificate: {e}");
+        AuthErrorReason::CertParseFailed
+    })?;
+    let (_, ca_pem) = x509_parser::pem::parse_x509_pem(decoded.as_bytes()).map_err(|e| {
+        warn!(op_id, "Failed to parse cert PEM: {e}");
+        AuthErrorReason::CertParseFailed
+    })?;
+    let cert = ca_pem.parse_x509().map_err(|e| {
+        warn!(op_id, "Failed to parse client cert: {e}");
+        AuthErrorReason::CertParseFailed
+    })?;
+    let app_id_bytes = cert
         .get_app_id()
-        .context("Failed to get app_id from client cert")?
-    else {
-        bail!("No app_id found in client cert");
-    };
+        .map_err(|e| {
+            warn!(op_id, "Failed to read app_id extension: {e}");
+            AuthErrorReason::CertParseFailed
+        })?
+        .ok_or(AuthErrorReason::NoAppId)?;
     Ok(hex::encode(app_id_bytes))
 }
 
+/// Health check response, including the currently-live TLS identity so
+/// operators can confirm a hot-reloaded rotation took effect.
+#[derive(serde::Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    tls_fingerprint: String,
+    tls_not_after: String,
+}
+
 /// Health check endpoint
 #[get("/health")]
-fn health_handler() -> Status {
-    Status::Ok
+fn health_handler(cert_store: &State<Arc<CertStore>>) -> Json<HealthResponse> {
+    let material = cert_store.current();
+    Json(HealthResponse {
+        status: "ok",
+        tls_fingerprint: material.fingerprint.clone(),
+        tls_not_after: material.not_after.clone(),
+    })
 }
 
-/// Run auth service with configuration from main figment
-pub(crate) async fn run_auth_service(main_figment: &rocket::figment::Figment) -> Result<()> {
-    // Create Rocket figment for auth service using the auth section
+/// Builds the Rocket figment for the auth service from the raw `auth`
+/// section. Rocket's own `Config` (which `rocket::custom` extracts during
+/// ignite regardless of whether `.launch()` or `.launch_on(listener)` ends
+/// up binding it) requires `address: IpAddr`/`port: u16`; `AuthConfig`'s
+/// `unix:/path` form isn't one, so merging it straight through would make
+/// `Config` extraction fail before the unix listener is ever bound.
+/// Override `address`/`port` with values Rocket itself never binds on the
+/// unix path once we know the real address isn't an `IpAddr`.
+fn build_auth_figment(
+    auth_section: rocket::figment::value::Value,
+    auth_config: &AuthConfig,
+) -> rocket::figment::Figment {
     let figment = rocket::figment::Figment::new()
         .merge(rocket::Config::default())
-        .merge(Serialized::defaults(
-            main_figment
-                .find_value("auth")
-                .context("auth section not found")?,
-        ));
-
-    let _rocket = rocket::custom(figment)
-        .mount("/", routes![auth_handler, health_handler])
-        .launch()
-        .await
-        .map_err(|e| anyhow::anyhow!("Rocket launch error: {}", e))?;
+        .merge(Serialized::defaults(auth_section));
+
+    match &auth_config.address {
+        ListenAddress::Unix(_) => figment.merge(("address", "127.0.0.1")).merge(("port", 0)),
+        ListenAddress::Tcp(_) => figment,
+    }
+}
+
+/// Loads and starts watching the TLS material shared by every listener in
+/// the process. Construct this once in the caller and pass the same `Arc`
+/// to both `run_auth_service` and `run_client_service`, rather than letting
+/// each load and poll its own copy of `cert_file`/`key_file`/`ca_file` —
+/// two independently-reloading `CertStore`s can briefly disagree on which
+/// material is current after a rotation.
+pub(crate) fn load_cert_store(main_figment: &rocket::figment::Figment) -> Result<Arc<CertStore>> {
+    let tls: crate::config::TlsConfig = main_figment
+        .extract_inner("tls")
+        .context("tls section not found")?;
+    let cert_store = CertStore::load(tls).context("failed to load initial TLS material")?;
+    cert_store.clone().watch();
+    Ok(cert_store)
+}
+
+/// Run auth service with configuration from main figment
+pub(crate) async fn run_auth_service(
+    main_figment: &rocket::figment::Figment,
+    cert_store: Arc<CertStore>,
+) -> Result<()> {
+    let auth_section = main_figment
+        .find_value("auth")
+        .context("auth section not found")?;
+    let auth_config: AuthConfig = auth_section
+        .clone()
+        .deserialize()
+        .context("failed to parse auth config")?;
+
+    let mut figment = build_auth_figment(auth_section, &auth_config);
+
+    let tls: crate::config::TlsConfig = main_figment
+        .extract_inner("tls")
+        .context("tls section not found")?;
+
+    if auth_config.in_process_mtls {
+        figment = with_mutual_tls(figment, &tls, !auth_config.allow_header_fallback);
+    }
+
+    let allow_header_fallback = AllowHeaderFallback(auth_config.allow_header_fallback);
+    let policy = Policy::new(auth_config.policies);
+
+    let hawk_config: HawkConfig = main_figment
+        .extract_inner("hawk")
+        .unwrap_or_default();
+
+    let sni_routes: Vec<SniRoute> = main_figment
+        .extract_inner("client.sni_routes")
+        .unwrap_or_default();
+
+    let built = rocket::custom(figment)
+        .manage(policy)
+        .manage(cert_store)
+        .manage(hawk_config)
+        .manage(sni_routes)
+        .manage(allow_header_fallback)
+        .mount("/", routes![auth_handler, health_handler]);
+
+    // A unix socket path keeps the auth endpoint off the network entirely,
+    // locking it down to filesystem permissions for the nginx co-located
+    // `auth_request` integration.
+    match &auth_config.address {
+        ListenAddress::Tcp(_) => {
+            let _rocket = built
+                .launch()
+                .await
+                .map_err(|e| anyhow::anyhow!("Rocket launch error: {}", e))?;
+        }
+        ListenAddress::Unix(path) => {
+            remove_stale_socket(path)?;
+            let listener = rocket::listener::unix::UnixListener::bind(path)
+                .await
+                .with_context(|| format!("failed to bind unix socket {}", path.display()))?;
+            let _cleanup = UnixSocketCleanup::new(path.clone());
+            let _rocket = built
+                .launch_on(listener)
+                .await
+                .map_err(|e| anyhow::anyhow!("Rocket launch error: {}", e))?;
+        }
+    }
 
     Ok(())
 }
+
+/// Runs the client-facing listener described by `ClientConfig`: terminates
+/// mTLS in-process with `AppIdClientCertVerifier` so the caller's `app_id`
+/// is taken from a chain-verified cert rather than a header, never reaching
+/// the nginx fallback path at all. The server identity presented is
+/// resolved per-SNI from `ClientConfig::sni_routes` (see `SniCertResolver`),
+/// so one listener fronts every app in the mesh. Each authenticated
+/// connection's `app_id` is logged; HTTP routing on this listener is a
+/// follow-up, not yet wired up here.
+pub(crate) async fn run_client_service(
+    main_figment: &rocket::figment::Figment,
+    cert_store: Arc<CertStore>,
+) -> Result<()> {
+    let client_config: ClientConfig = main_figment
+        .extract_inner("client")
+        .context("client section not found")?;
+    let tls: crate::config::TlsConfig = main_figment
+        .extract_inner("tls")
+        .context("tls section not found")?;
+
+    let tls_config = Arc::new(build_client_tls_config(
+        &cert_store,
+        &tls,
+        &client_config.sni_routes,
+        true,
+    )?);
+    let acceptor = tokio_rustls::TlsAcceptor::from(tls_config);
+
+    match &client_config.address {
+        ListenAddress::Tcp(ip) => {
+            let listener = tokio::net::TcpListener::bind((*ip, client_config.port))
+                .await
+                .with_context(|| format!("failed to bind {ip}:{}", client_config.port))?;
+            info!("Client mTLS listener on {ip}:{}", client_config.port);
+            loop {
+                let (stream, peer) = listener
+                    .accept()
+                    .await
+                    .context("failed to accept client connection")?;
+                let acceptor = acceptor.clone();
+                tokio::spawn(handle_client_connection(acceptor, stream, peer.to_string()));
+            }
+        }
+        ListenAddress::Unix(path) => {
+            remove_stale_socket(path)?;
+            let listener = tokio::net::UnixListener::bind(path)
+                .with_context(|| format!("failed to bind unix socket {}", path.display()))?;
+            let _cleanup = UnixSocketCleanup::new(path.clone());
+            info!("Client mTLS listener on unix:{}", path.display());
+            loop {
+                let (stream, _addr) = listener
+                    .accept()
+                    .await
+                    .context("failed to accept client connection")?;
+                let acceptor = acceptor.clone();
+                tokio::spawn(handle_client_connection(
+                    acceptor,
+                    stream,
+                    path.display().to_string(),
+                ));
+            }
+        }
+    }
+}
+
+/// Completes the mTLS handshake on one accepted connection and logs the
+/// `app_id` `AppIdClientCertVerifier` resolved for it.
+async fn handle_client_connection<S>(acceptor: tokio_rustls::TlsAcceptor, stream: S, peer: String)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let tls_stream = match acceptor.accept(stream).await {
+        Ok(tls_stream) => tls_stream,
+        Err(e) => {
+            warn!("client mTLS handshake with {peer} failed: {e}");
+            return;
+        }
+    };
+    match app_id_of_peer(tls_stream.get_ref().1) {
+        Ok(app_id) => info!("Client {peer} authenticated as app_id: {app_id}"),
+        Err(e) => warn!("client {peer} completed TLS handshake but has no usable app_id: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn sample_auth_config(address: ListenAddress) -> AuthConfig {
+        AuthConfig {
+            address,
+            port: 0,
+            policies: Vec::new(),
+            in_process_mtls: false,
+            allow_header_fallback: true,
+        }
+    }
+
+    fn auth_section_value(auth_config: &AuthConfig) -> rocket::figment::value::Value {
+        rocket::figment::Figment::from(Serialized::defaults(auth_config))
+            .find_value("")
+            .expect("serialize auth config to a figment value")
+    }
+
+    #[test]
+    fn unix_address_does_not_break_rocket_config_extraction() {
+        let auth_config = sample_auth_config(ListenAddress::Unix(PathBuf::from(
+            "/tmp/dstack-mesh-test.sock",
+        )));
+        let figment = build_auth_figment(auth_section_value(&auth_config), &auth_config);
+
+        let extracted: rocket::Config = figment
+            .extract()
+            .expect("rocket Config must extract even when AuthConfig::address is a unix path");
+        assert_eq!(extracted.address.to_string(), "127.0.0.1");
+    }
+
+    #[test]
+    fn tcp_address_is_passed_through_unmodified() {
+        let auth_config = sample_auth_config(ListenAddress::Tcp("10.0.0.5".parse().unwrap()));
+        let figment = build_auth_figment(auth_section_value(&auth_config), &auth_config);
+
+        let extracted: rocket::Config = figment
+            .extract()
+            .expect("rocket Config must extract for a tcp address");
+        assert_eq!(extracted.address.to_string(), "10.0.0.5");
+    }
+}